@@ -0,0 +1,450 @@
+//! Async, non-blocking listener built on tokio.
+//!
+//! [`SQSListen::listen`](crate::SQSListen::listen) spawns a clokwerk scheduler thread and blocks
+//! on `.sync()` for every `receive_message`/`delete_message` call, serializing all I/O on one
+//! thread. [`SQSListener`] instead drives the rusoto futures directly so a caller can `.await`
+//! them on a tokio runtime and process many messages concurrently.
+//!
+//! ```rust,no_run
+//! use sqslisten::{ReceiveMessageRequest, Region, SQSListenerBuilder};
+//!
+//! # async fn run() {
+//! let listener = SQSListenerBuilder::new(Region::UsEast1)
+//!     .queue_url("<queue_url>")
+//!     .build();
+//!
+//! listener
+//!     .listen(ReceiveMessageRequest::default(), |msg, _err| {
+//!         println!("Message received: {:?}", msg);
+//!         async { Ok(()) }
+//!     })
+//!     .await;
+//! # }
+//! ```
+
+use crate::shutdown::InFlight;
+use crate::{exceeds_max_receive_count, AsyncShutdownHandle, HandlerError, APPROXIMATE_RECEIVE_COUNT};
+use futures::future::join_all;
+use rusoto_core::{Region, RusotoError};
+use rusoto_sqs::{
+    ChangeMessageVisibilityRequest, DeleteMessageBatchRequest, DeleteMessageBatchRequestEntry,
+    DeleteMessageRequest, Message, ReceiveMessageError, ReceiveMessageRequest,
+    ReceiveMessageResult, SendMessageRequest, Sqs, SqsClient,
+};
+use std::future::Future;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// `receive_message` accepts at most 10 messages per call, and `delete_message_batch` accepts
+/// at most 10 entries per call.
+const MAX_BATCH_SIZE: i64 = 10;
+
+/// SQS's maximum long-poll wait, used as the default `wait_time_seconds` so a caller who leaves
+/// it unset gets long-polling (and therefore natural pacing) instead of a busy loop.
+const DEFAULT_WAIT_TIME_SECONDS: i64 = 20;
+
+/// How long to back off after a `receive_message` error (e.g. bad credentials, throttling)
+/// before polling again, so a persistent failure doesn't spin against the API with zero delay.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Builds an [`SQSListener`], mirroring the configuration available on [`crate::SQSListen`].
+pub struct SQSListenerBuilder {
+    region: Region,
+    queue_url: Option<String>,
+    max_receive_count: Option<i64>,
+    dead_letter_queue_url: Option<String>,
+    retry_visibility_timeout: Option<i64>,
+    heartbeat_visibility_timeout: Option<Duration>,
+    heartbeat_max_extensions: Option<u32>,
+}
+
+impl SQSListenerBuilder {
+    pub fn new(region: Region) -> Self {
+        SQSListenerBuilder {
+            region,
+            queue_url: None,
+            max_receive_count: None,
+            dead_letter_queue_url: None,
+            retry_visibility_timeout: None,
+            heartbeat_visibility_timeout: None,
+            heartbeat_max_extensions: None,
+        }
+    }
+
+    pub fn queue_url(mut self, queue_url: impl Into<String>) -> Self {
+        self.queue_url = Some(queue_url.into());
+        self
+    }
+
+    /// Configures a dead-letter queue and the number of redeliveries (read from
+    /// `ApproximateReceiveCount`) a message is allowed before it is forwarded there instead of
+    /// being retried again.
+    pub fn dead_letter_queue(mut self, queue_url: impl Into<String>, max_receive_count: i64) -> Self {
+        self.dead_letter_queue_url = Some(queue_url.into());
+        self.max_receive_count = Some(max_receive_count);
+        self
+    }
+
+    /// Configures how long (in seconds) a failed message's visibility timeout is extended to
+    /// when the handler returns an error, controlling the retry delay.
+    pub fn retry_visibility_timeout(mut self, seconds: i64) -> Self {
+        self.retry_visibility_timeout = Some(seconds);
+        self
+    }
+
+    /// Enables a visibility-timeout heartbeat: while a handler is running, the listener
+    /// periodically re-extends the in-flight message's invisibility window back up to
+    /// `visibility_timeout`, refreshing every `visibility_timeout / 2` so the refresh always has
+    /// margin before the previous one would expire (the first refresh fires at the halfway
+    /// point, not after a full `visibility_timeout` has already elapsed). `max_extensions` bounds
+    /// how many times a single message is extended, so a handler that never returns eventually
+    /// gives up instead of extending forever.
+    pub fn heartbeat(mut self, visibility_timeout: Duration, max_extensions: u32) -> Self {
+        self.heartbeat_visibility_timeout = Some(visibility_timeout);
+        self.heartbeat_max_extensions = Some(max_extensions);
+        self
+    }
+
+    pub fn build(self) -> SQSListener {
+        SQSListener {
+            sqs_client: SqsClient::new(self.region),
+            queue_url: self.queue_url.expect("queue_url is required"),
+            max_receive_count: self.max_receive_count,
+            dead_letter_queue_url: self.dead_letter_queue_url,
+            retry_visibility_timeout: self.retry_visibility_timeout,
+            heartbeat_visibility_timeout: self.heartbeat_visibility_timeout,
+            heartbeat_max_extensions: self.heartbeat_max_extensions,
+            in_flight: InFlight::new(),
+        }
+    }
+}
+
+/// Async counterpart of [`crate::SQSListen`]. Polls the queue and awaits the handler directly on
+/// the caller's tokio runtime instead of blocking a dedicated thread per poll.
+pub struct SQSListener {
+    sqs_client: SqsClient,
+    queue_url: String,
+    max_receive_count: Option<i64>,
+    dead_letter_queue_url: Option<String>,
+    retry_visibility_timeout: Option<i64>,
+    heartbeat_visibility_timeout: Option<Duration>,
+    heartbeat_max_extensions: Option<u32>,
+    in_flight: InFlight,
+}
+
+impl SQSListener {
+    /// Returns a handle that can be used to gracefully stop [`Self::listen`] or
+    /// [`Self::listen_batch`] from another task: new polls stop first, then in-flight handler
+    /// invocations get a grace period to finish.
+    pub fn shutdown_handle(&self) -> AsyncShutdownHandle {
+        AsyncShutdownHandle::new(self.in_flight.clone())
+    }
+
+    /// Polls `input.queue_url` in a loop, awaiting `handler` concurrently for every message in a
+    /// received batch and acknowledging each one only once its handler resolves to `Ok(())`.
+    /// Returns once a [`Self::shutdown_handle`] requests a stop.
+    pub async fn listen<F, Fut>(&self, input: ReceiveMessageRequest, handler: F)
+    where
+        F: Fn(Option<&Message>, Option<RusotoError<ReceiveMessageError>>) -> Fut,
+        Fut: Future<Output = Result<(), HandlerError>>,
+    {
+        let mut input = input;
+        input.queue_url = self.queue_url.clone();
+        input.wait_time_seconds = Some(input.wait_time_seconds.unwrap_or(DEFAULT_WAIT_TIME_SECONDS));
+
+        let mut attribute_names = input.attribute_names.unwrap_or_default();
+        if !attribute_names.iter().any(|a| a == APPROXIMATE_RECEIVE_COUNT) {
+            attribute_names.push(APPROXIMATE_RECEIVE_COUNT.to_string());
+        }
+        input.attribute_names = Some(attribute_names);
+
+        while !self.in_flight.is_stopping() {
+            match self.sqs_client.receive_message(input.clone()).await {
+                Ok(response) => self.process_response(&response, &handler).await,
+                Err(err) => {
+                    let _ignored = handler(None, Some(err)).await;
+                    tokio::time::sleep(ERROR_BACKOFF).await;
+                }
+            }
+        }
+    }
+
+    /// Like [`Self::listen`], but receives up to 10 messages per poll and hands the whole batch
+    /// to `on_batch` at once. On `Ok(())` the batch is acknowledged with a single
+    /// `delete_message_batch` call instead of one `delete_message` per message; on `Err` none of
+    /// the batch is deleted, so every message in it is redelivered after its visibility timeout.
+    pub async fn listen_batch<F, Fut>(&self, input: ReceiveMessageRequest, on_batch: F)
+    where
+        F: Fn(&[Message]) -> Fut,
+        Fut: Future<Output = Result<(), HandlerError>>,
+    {
+        let mut input = input;
+        input.queue_url = self.queue_url.clone();
+        input.max_number_of_messages = Some(
+            input
+                .max_number_of_messages
+                .map_or(MAX_BATCH_SIZE, |n| n.min(MAX_BATCH_SIZE)),
+        );
+        input.wait_time_seconds = Some(input.wait_time_seconds.unwrap_or(DEFAULT_WAIT_TIME_SECONDS));
+
+        let mut attribute_names = input.attribute_names.unwrap_or_default();
+        if !attribute_names.iter().any(|a| a == APPROXIMATE_RECEIVE_COUNT) {
+            attribute_names.push(APPROXIMATE_RECEIVE_COUNT.to_string());
+        }
+        input.attribute_names = Some(attribute_names);
+
+        while !self.in_flight.is_stopping() {
+            match self.sqs_client.receive_message(input.clone()).await {
+                Ok(response) => self.process_batch(&response, &on_batch).await,
+                Err(_ignored) => {
+                    tokio::time::sleep(ERROR_BACKOFF).await;
+                }
+            }
+        }
+    }
+
+    async fn process_batch<F, Fut>(&self, response: &ReceiveMessageResult, on_batch: &F)
+    where
+        F: Fn(&[Message]) -> Fut,
+        Fut: Future<Output = Result<(), HandlerError>>,
+    {
+        let messages = match &response.messages {
+            Some(messages) if !messages.is_empty() => messages,
+            _ => return,
+        };
+
+        let _guards: Vec<_> = messages.iter().map(|_| self.in_flight.guard()).collect();
+        let heartbeats: Vec<JoinHandle<()>> = messages
+            .iter()
+            .filter_map(|message| self.spawn_heartbeat(message))
+            .collect();
+        let result = on_batch(messages).await;
+        for heartbeat in heartbeats {
+            heartbeat.abort();
+        }
+
+        if result.is_ok() {
+            self.ack_batch(messages).await;
+        }
+    }
+
+    async fn ack_batch(&self, messages: &[Message]) {
+        let entries = batch_entries(messages);
+        if entries.is_empty() {
+            return;
+        }
+
+        // Entries that come back in `result.failed` are left un-deleted so SQS redelivers them;
+        // there is nothing further to do with them here.
+        let _ignored = self
+            .sqs_client
+            .delete_message_batch(DeleteMessageBatchRequest {
+                queue_url: self.queue_url.clone(),
+                entries,
+            })
+            .await;
+    }
+
+    /// Awaits `handler` for every message in `response` concurrently (not one at a time), so a
+    /// slow message's handler doesn't hold up every other message in the same batch.
+    async fn process_response<F, Fut>(&self, response: &ReceiveMessageResult, handler: &F)
+    where
+        F: Fn(Option<&Message>, Option<RusotoError<ReceiveMessageError>>) -> Fut,
+        Fut: Future<Output = Result<(), HandlerError>>,
+    {
+        if let Some(messages) = &response.messages {
+            let deliveries = messages
+                .iter()
+                .map(|message| self.process_message(message, handler));
+            join_all(deliveries).await;
+        }
+    }
+
+    async fn process_message<F, Fut>(&self, message: &Message, handler: &F)
+    where
+        F: Fn(Option<&Message>, Option<RusotoError<ReceiveMessageError>>) -> Fut,
+        Fut: Future<Output = Result<(), HandlerError>>,
+    {
+        let _guard = self.in_flight.guard();
+        let heartbeat = self.spawn_heartbeat(message);
+        let result = handler(Some(message), None).await;
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.abort();
+        }
+
+        match result {
+            Ok(()) => self.ack_message(message).await,
+            Err(_) => self.handle_failure(message).await,
+        }
+    }
+
+    /// Spawns a background task that periodically re-extends `message`'s visibility timeout
+    /// while its handler is still running, if a heartbeat is configured. The caller is
+    /// responsible for aborting the returned task once the handler completes.
+    fn spawn_heartbeat(&self, message: &Message) -> Option<JoinHandle<()>> {
+        let visibility_timeout = self.heartbeat_visibility_timeout?;
+        let receipt_handle = message.receipt_handle.clone()?;
+        let max_extensions = self.heartbeat_max_extensions.unwrap_or(u32::MAX);
+        let sqs_client = self.sqs_client.clone();
+        let queue_url = self.queue_url.clone();
+        let refresh_interval = heartbeat_refresh_interval(visibility_timeout);
+        let visibility_timeout_secs = visibility_timeout.as_secs() as i64;
+
+        Some(tokio::spawn(async move {
+            for _ in 0..max_extensions {
+                tokio::time::sleep(refresh_interval).await;
+                let _ignored = sqs_client
+                    .change_message_visibility(ChangeMessageVisibilityRequest {
+                        queue_url: queue_url.clone(),
+                        receipt_handle: receipt_handle.clone(),
+                        visibility_timeout: visibility_timeout_secs,
+                    })
+                    .await;
+            }
+        }))
+    }
+
+    async fn handle_failure(&self, message: &Message) {
+        if let Some(max_receive_count) = self.max_receive_count {
+            let receive_count = message
+                .attributes
+                .as_ref()
+                .and_then(|attrs| attrs.get(APPROXIMATE_RECEIVE_COUNT))
+                .and_then(|count| count.parse::<i64>().ok())
+                .unwrap_or(0);
+
+            if exceeds_max_receive_count(receive_count, max_receive_count) {
+                self.send_to_dead_letter_queue(message).await;
+                return;
+            }
+        }
+
+        if let Some(timeout) = self.retry_visibility_timeout {
+            self.change_message_visibility(message, timeout).await;
+        }
+    }
+
+    /// Forwards `message` to the configured dead-letter queue and only then deletes it from the
+    /// source queue, so a failed `send_message` (throttling, permissions, network) leaves the
+    /// message on the source queue to be redelivered instead of silently dropping it.
+    async fn send_to_dead_letter_queue(&self, message: &Message) {
+        let dead_letter_queue_url = match &self.dead_letter_queue_url {
+            Some(dead_letter_queue_url) => dead_letter_queue_url,
+            None => return,
+        };
+
+        let sent = self
+            .sqs_client
+            .send_message(SendMessageRequest {
+                queue_url: dead_letter_queue_url.clone(),
+                message_body: message.body.clone().unwrap_or_default(),
+                ..SendMessageRequest::default()
+            })
+            .await;
+
+        if sent.is_ok() {
+            self.ack_message(message).await;
+        }
+    }
+
+    async fn change_message_visibility(&self, message: &Message, visibility_timeout: i64) {
+        if let Some(receipt_handle) = &message.receipt_handle {
+            let _ignored = self
+                .sqs_client
+                .change_message_visibility(ChangeMessageVisibilityRequest {
+                    queue_url: self.queue_url.clone(),
+                    receipt_handle: receipt_handle.clone(),
+                    visibility_timeout,
+                })
+                .await;
+        }
+    }
+
+    async fn ack_message(&self, message: &Message) {
+        if let Some(receipt_handle) = &message.receipt_handle {
+            let _ignored = self
+                .sqs_client
+                .delete_message(DeleteMessageRequest {
+                    queue_url: self.queue_url.clone(),
+                    receipt_handle: receipt_handle.clone(),
+                })
+                .await;
+        }
+    }
+}
+
+/// The cadence at which a heartbeat re-extends a message's visibility timeout: half of
+/// `visibility_timeout`, so every refresh lands with margin before the previous one (or the
+/// message's initial visibility timeout) would expire, instead of refreshing only after a full
+/// `visibility_timeout` has already elapsed.
+fn heartbeat_refresh_interval(visibility_timeout: Duration) -> Duration {
+    visibility_timeout / 2
+}
+
+/// Builds the `delete_message_batch` entries for a received batch, skipping any message that
+/// somehow has no `receipt_handle` rather than failing the whole batch's acknowledgment. Each
+/// entry's `id` is the message's index within `messages`, not within the returned entries, so it
+/// can be used to line up `result.failed` against the original batch if that's ever needed.
+fn batch_entries(messages: &[Message]) -> Vec<DeleteMessageBatchRequestEntry> {
+    messages
+        .iter()
+        .enumerate()
+        .filter_map(|(index, message)| {
+            message
+                .receipt_handle
+                .clone()
+                .map(|receipt_handle| DeleteMessageBatchRequestEntry {
+                    id: index.to_string(),
+                    receipt_handle,
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message_with_receipt(receipt_handle: Option<&str>) -> Message {
+        Message {
+            receipt_handle: receipt_handle.map(str::to_string),
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn heartbeat_refresh_interval_is_half_the_visibility_timeout() {
+        assert_eq!(
+            heartbeat_refresh_interval(Duration::from_secs(30)),
+            Duration::from_secs(15)
+        );
+        assert_eq!(
+            heartbeat_refresh_interval(Duration::from_secs(1)),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn batch_entries_skips_messages_without_a_receipt_handle() {
+        let messages = vec![
+            message_with_receipt(Some("a")),
+            message_with_receipt(None),
+            message_with_receipt(Some("c")),
+        ];
+
+        let entries = batch_entries(&messages);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, "0");
+        assert_eq!(entries[0].receipt_handle, "a");
+        assert_eq!(entries[1].id, "2");
+        assert_eq!(entries[1].receipt_handle, "c");
+    }
+
+    #[test]
+    fn batch_entries_is_empty_when_no_message_has_a_receipt_handle() {
+        let messages = vec![message_with_receipt(None), message_with_receipt(None)];
+
+        assert!(batch_entries(&messages).is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+//! Fan-out a single poll loop to many independent consumers via a broadcast channel.
+//!
+//! Each call to [`crate::SQSListen::listen`] or [`crate::SQSListener::listen`] polls SQS on its
+//! own, so running several independent processors against one queue means several pollers
+//! competing for (and accidentally stealing) each other's messages. [`SQSBroadcastListener`]
+//! instead polls the queue once and publishes every received message onto a broadcast channel
+//! that any number of subscribers can listen to, acknowledging the message only once every
+//! subscriber that was attached at delivery time has reported success.
+//!
+//! ```rust,no_run
+//! use sqslisten::{ReceiveMessageRequest, Region, SQSBroadcastListenerBuilder};
+//!
+//! # async fn run() {
+//! let listener = SQSBroadcastListenerBuilder::new(Region::UsEast1)
+//!     .queue_url("<queue_url>")
+//!     .build();
+//!
+//! let mut subscriber = listener.subscribe();
+//! tokio::spawn(async move {
+//!     while let Ok(delivery) = subscriber.recv().await {
+//!         println!("Message received: {:?}", delivery.message);
+//!         delivery.ack();
+//!     }
+//! });
+//!
+//! listener.run(ReceiveMessageRequest::default()).await;
+//! # }
+//! ```
+
+use crate::shutdown::InFlight;
+use crate::AsyncShutdownHandle;
+use rusoto_core::Region;
+use rusoto_sqs::{DeleteMessageRequest, Message, ReceiveMessageRequest, Sqs, SqsClient};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+
+const DEFAULT_CHANNEL_CAPACITY: usize = 128;
+
+/// SQS's maximum long-poll wait, used as the default `wait_time_seconds` so a caller who leaves
+/// it unset gets long-polling (and therefore natural pacing) instead of a busy loop.
+const DEFAULT_WAIT_TIME_SECONDS: i64 = 20;
+
+/// How long to back off after a `receive_message` error before polling again, so a persistent
+/// failure doesn't spin against the API with zero delay.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Builds an [`SQSBroadcastListener`].
+pub struct SQSBroadcastListenerBuilder {
+    region: Region,
+    queue_url: Option<String>,
+    channel_capacity: usize,
+}
+
+impl SQSBroadcastListenerBuilder {
+    pub fn new(region: Region) -> Self {
+        SQSBroadcastListenerBuilder {
+            region,
+            queue_url: None,
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+        }
+    }
+
+    pub fn queue_url(mut self, queue_url: impl Into<String>) -> Self {
+        self.queue_url = Some(queue_url.into());
+        self
+    }
+
+    /// Sets the broadcast channel's buffer size, i.e. how many in-flight deliveries a slow
+    /// subscriber can lag behind before it starts missing messages.
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity;
+        self
+    }
+
+    pub fn build(self) -> SQSBroadcastListener {
+        let (sender, _receiver) = broadcast::channel(self.channel_capacity);
+        SQSBroadcastListener {
+            sqs_client: SqsClient::new(self.region),
+            queue_url: self.queue_url.expect("queue_url is required"),
+            sender,
+            in_flight: InFlight::new(),
+        }
+    }
+}
+
+/// A single message delivered to a subscriber, shared with every other subscriber that was
+/// attached when it was received. The message is only deleted from the queue once every
+/// subscriber has called [`Delivery::ack`] (or left to be redelivered if any subscriber calls
+/// [`Delivery::nack`]).
+#[derive(Clone)]
+pub struct Delivery {
+    pub message: Arc<Message>,
+    state: Arc<DeliveryState>,
+}
+
+struct DeliveryState {
+    pending: AtomicUsize,
+    failed: AtomicBool,
+    done: Notify,
+}
+
+impl Delivery {
+    /// Reports that this subscriber finished processing the message successfully.
+    pub fn ack(&self) {
+        self.complete(false);
+    }
+
+    /// Reports that this subscriber failed to process the message, so it is left on the queue
+    /// for redelivery even if every other subscriber acks.
+    pub fn nack(&self) {
+        self.complete(true);
+    }
+
+    fn complete(&self, failed: bool) {
+        if failed {
+            self.state.failed.store(true, Ordering::SeqCst);
+        }
+        if self.state.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.state.done.notify_one();
+        }
+    }
+}
+
+/// Polls a queue once and fans each message out to any number of registered subscribers. See
+/// the [module docs](self) for an overview.
+pub struct SQSBroadcastListener {
+    sqs_client: SqsClient,
+    queue_url: String,
+    sender: broadcast::Sender<Delivery>,
+    in_flight: InFlight,
+}
+
+impl SQSBroadcastListener {
+    /// Registers a new independent consumer. Subscribers may attach and detach (by dropping the
+    /// returned receiver) at any point while [`Self::run`] is polling.
+    pub fn subscribe(&self) -> broadcast::Receiver<Delivery> {
+        self.sender.subscribe()
+    }
+
+    /// Returns a handle that can be used to gracefully stop [`Self::run`] from another task: new
+    /// polls stop first, then in-flight deliveries get a grace period to finish.
+    pub fn shutdown_handle(&self) -> AsyncShutdownHandle {
+        AsyncShutdownHandle::new(self.in_flight.clone())
+    }
+
+    /// Polls `input.queue_url` in a loop, publishing every received message to all current
+    /// subscribers and acknowledging it only once all of them have reported success. A message
+    /// delivered to zero subscribers is left unacknowledged and is redelivered once its
+    /// visibility timeout expires.
+    /// Returns once a [`Self::shutdown_handle`] requests a stop.
+    pub async fn run(&self, input: ReceiveMessageRequest) {
+        let mut input = input;
+        input.queue_url = self.queue_url.clone();
+        input.wait_time_seconds = Some(input.wait_time_seconds.unwrap_or(DEFAULT_WAIT_TIME_SECONDS));
+
+        while !self.in_flight.is_stopping() {
+            match self.sqs_client.receive_message(input.clone()).await {
+                Ok(response) => {
+                    if let Some(messages) = response.messages {
+                        for message in messages {
+                            self.dispatch(message).await;
+                        }
+                    }
+                }
+                Err(_ignored) => {
+                    tokio::time::sleep(ERROR_BACKOFF).await;
+                }
+            }
+        }
+    }
+
+    async fn dispatch(&self, message: Message) {
+        let _guard = self.in_flight.guard();
+        // `pending` starts at 1, representing this dispatch's own placeholder unit, released via
+        // the `fetch_sub(1)` below once the real subscriber count has been registered.
+        let state = Arc::new(DeliveryState {
+            pending: AtomicUsize::new(1),
+            failed: AtomicBool::new(false),
+            done: Notify::new(),
+        });
+        let delivery = Delivery {
+            message: Arc::new(message),
+            state: state.clone(),
+        };
+
+        // Reserve the real subscriber count against `pending` *before* `send()` delivers the
+        // message to them. Reserving it from `send`'s return value instead (i.e. after delivery)
+        // leaves a window where a subscriber's `ack()`/`nack()` can `fetch_sub(1)` before this
+        // task gets back around to `fetch_add`, driving `pending` to zero with other subscribers
+        // still outstanding — the bias-of-1 placeholder only ever absorbs one such premature
+        // decrement, so it protects a single subscriber but not two or more.
+        let subscriber_count = self.sender.receiver_count();
+        if subscriber_count == 0 {
+            return;
+        }
+        state.pending.fetch_add(subscriber_count, Ordering::SeqCst);
+
+        if self.sender.send(delivery.clone()).is_err() {
+            // Every receiver dropped between the `receiver_count()` read above and `send()`;
+            // release the reservation so `notified()` below doesn't wait on acks that will never
+            // arrive.
+            state.pending.fetch_sub(subscriber_count, Ordering::SeqCst);
+            return;
+        }
+
+        if state.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            state.done.notify_one();
+        }
+        state.done.notified().await;
+
+        if !state.failed.load(Ordering::SeqCst) {
+            self.ack_message(&delivery.message).await;
+        }
+    }
+
+    async fn ack_message(&self, message: &Message) {
+        if let Some(receipt_handle) = &message.receipt_handle {
+            let _ignored = self
+                .sqs_client
+                .delete_message(DeleteMessageRequest {
+                    queue_url: self.queue_url.clone(),
+                    receipt_handle: receipt_handle.clone(),
+                })
+                .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_sqs::Message;
+
+    fn placeholder_delivery() -> (Arc<DeliveryState>, Delivery) {
+        let state = Arc::new(DeliveryState {
+            pending: AtomicUsize::new(1),
+            failed: AtomicBool::new(false),
+            done: Notify::new(),
+        });
+        let delivery = Delivery {
+            message: Arc::new(Message::default()),
+            state: state.clone(),
+        };
+        (state, delivery)
+    }
+
+    // Mirrors `dispatch`'s own ordering: the real subscriber count is reserved against `pending`
+    // *before* any subscriber can observe (and ack/nack) the message.
+    fn register_subscribers(state: &DeliveryState, subscriber_count: usize) {
+        state.pending.fetch_add(subscriber_count, Ordering::SeqCst);
+        if state.pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+            state.done.notify_one();
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_complete_until_every_registered_subscriber_acks() {
+        let (state, delivery) = placeholder_delivery();
+        register_subscribers(&state, 2);
+
+        // Only one of the two registered subscribers has reported in so far. Under the old
+        // logic (reserving the count from `send`'s return value *after* delivery), a lone ack
+        // could already race ahead of the reservation and drive `pending` to zero here; with the
+        // count reserved up front that can't happen, so `notified()` must still be pending.
+        delivery.clone().ack();
+        let premature = tokio::time::timeout(Duration::from_millis(50), state.done.notified()).await;
+        assert!(
+            premature.is_err(),
+            "dispatch completed with a subscriber still outstanding"
+        );
+
+        // The second subscriber finally reports in, and only now does it complete.
+        delivery.ack();
+        state.done.notified().await;
+        assert!(!state.failed.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn nack_marks_failed_even_if_other_subscribers_ack() {
+        let (state, delivery) = placeholder_delivery();
+        register_subscribers(&state, 2);
+
+        let acking = delivery.clone();
+        let nacking = delivery.clone();
+        tokio::join!(async move { acking.ack() }, async move { nacking.nack() });
+
+        state.done.notified().await;
+        assert!(state.failed.load(Ordering::SeqCst));
+    }
+}
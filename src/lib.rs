@@ -1,7 +1,16 @@
 //! # SQSListen, a simple listener for AWS SQS queue.
 //!
 //! It allows you to set listener to your AWS SQS queue which will ask for the available messages in the queue and call the passed handler when the message received.
-//! Once message received and processed (does not matter if handler returns error or not) the message is removed from the queue.
+//! The message is only removed from the queue once the handler returns `Ok(())`. If the handler returns
+//! `Err(HandlerError)` the message is left in place so SQS redelivers it after its visibility timeout, optionally
+//! forwarding it to a configured dead-letter queue once `ApproximateReceiveCount` exceeds `max_receive_count`.
+//!
+//! [`SQSListen::listen`] blocks a dedicated thread on every poll; see [`async_listen`] for an
+//! async alternative built on tokio that `.await`s the rusoto futures directly.
+//!
+//! `listen` returns an [`SQSListenHandle`]. Its [`stop`](SQSListenHandle::stop) tears the poller
+//! down immediately; its [`shutdown`](SQSListenHandle::shutdown) instead stops new polls and
+//! waits for in-flight handlers to finish before stopping, for a clean drain during a redeploy.
 //!
 //! ## Usage
 //! ```rust
@@ -46,14 +55,42 @@ pub use rusoto_sqs::{
 };
 
 use clokwerk::{ScheduleHandle, Scheduler, TimeUnits};
-use rusoto_sqs::{Sqs, SqsClient};
+use rusoto_sqs::{
+    ChangeMessageVisibilityRequest, SendMessageRequest, Sqs, SqsClient,
+};
 use std::option::Option;
 use std::time::Duration;
 
+pub mod async_listen;
+pub mod broadcast;
+pub mod router;
+mod shutdown;
+
+pub use crate::async_listen::{SQSListener, SQSListenerBuilder};
+pub use crate::broadcast::{Delivery, SQSBroadcastListener, SQSBroadcastListenerBuilder};
+pub use crate::router::{RouteOutcome, SQSRouter, SQSRouterBuilder};
+pub use crate::shutdown::{AsyncShutdownHandle, ShutdownOutcome};
+
+use crate::shutdown::InFlight;
+
+pub(crate) const APPROXIMATE_RECEIVE_COUNT: &str = "ApproximateReceiveCount";
+
+/// Whether a message that has now been received `receive_count` times (per
+/// `ApproximateReceiveCount`) should be forwarded to a dead-letter queue instead of retried
+/// again, given a configured `max_receive_count`. Shared by [`SQSListen`] and
+/// [`crate::SQSListener`] so the threshold can't drift between the sync and async paths.
+pub(crate) fn exceeds_max_receive_count(receive_count: i64, max_receive_count: i64) -> bool {
+    receive_count > max_receive_count
+}
+
 #[derive(Clone)]
 pub struct SQSListen {
     sqs_client: SqsClient,
     queue_url: String,
+    max_receive_count: Option<i64>,
+    dead_letter_queue_url: Option<String>,
+    retry_visibility_timeout: Option<i64>,
+    in_flight: InFlight,
 }
 
 #[derive(Debug, Clone)]
@@ -68,6 +105,10 @@ impl SQSListen {
         SQSListen {
             sqs_client: SqsClient::new(region),
             queue_url: "".to_string(),
+            max_receive_count: None,
+            dead_letter_queue_url: None,
+            retry_visibility_timeout: None,
+            in_flight: InFlight::new(),
         }
     }
 
@@ -85,10 +126,30 @@ impl SQSListen {
         SQSListen {
             sqs_client: SqsClient::new_with(request_dispatcher, credentials_provider, region),
             queue_url: "".to_string(),
+            max_receive_count: None,
+            dead_letter_queue_url: None,
+            retry_visibility_timeout: None,
+            in_flight: InFlight::new(),
         }
     }
 
-    pub fn listen<F>(&mut self, input: ReceiveMessageRequest, handler: F) -> ScheduleHandle
+    /// Configures a dead-letter queue and the number of redeliveries (read from
+    /// `ApproximateReceiveCount`) a message is allowed before it is forwarded there
+    /// instead of being retried again.
+    pub fn with_dead_letter_queue(mut self, queue_url: String, max_receive_count: i64) -> Self {
+        self.dead_letter_queue_url = Some(queue_url);
+        self.max_receive_count = Some(max_receive_count);
+        self
+    }
+
+    /// Configures how long (in seconds) a failed message's visibility timeout is
+    /// extended to when the handler returns an error, controlling the retry delay.
+    pub fn with_retry_visibility_timeout(mut self, seconds: i64) -> Self {
+        self.retry_visibility_timeout = Some(seconds);
+        self
+    }
+
+    pub fn listen<F>(&mut self, input: ReceiveMessageRequest, handler: F) -> SQSListenHandle
     where
         F: Fn(
                 Option<&Message>,
@@ -103,6 +164,14 @@ impl SQSListen {
         self.queue_url = input.queue_url.clone();
         let sqs_client = self.sqs_client.clone();
         let that = self.clone();
+        let in_flight = self.in_flight.clone();
+
+        let mut input = input;
+        let mut attribute_names = input.attribute_names.unwrap_or_default();
+        if !attribute_names.iter().any(|a| a == APPROXIMATE_RECEIVE_COUNT) {
+            attribute_names.push(APPROXIMATE_RECEIVE_COUNT.to_string());
+        }
+        input.attribute_names = Some(attribute_names);
 
         let interval = match input.wait_time_seconds {
             Some(wait_time) => (wait_time as u32 + 1).seconds(),
@@ -111,6 +180,9 @@ impl SQSListen {
 
         let mut scheduler = Scheduler::new();
         scheduler.every(interval).run(move || {
+            if in_flight.is_stopping() {
+                return;
+            }
             match sqs_client.receive_message(input.clone()).sync() {
                 Ok(response) => that.process_response(&response, &handler),
                 Err(err) => {
@@ -118,7 +190,12 @@ impl SQSListen {
                 }
             }
         });
-        scheduler.watch_thread(Duration::from_millis(100))
+        let schedule_handle = scheduler.watch_thread(Duration::from_millis(100));
+
+        SQSListenHandle {
+            schedule_handle,
+            in_flight: self.in_flight.clone(),
+        }
     }
 
     fn process_response<F>(&self, response: &ReceiveMessageResult, handler: &F)
@@ -134,14 +211,78 @@ impl SQSListen {
         match &response.messages {
             Some(messages) => {
                 for message in messages {
-                    let _ignored = handler(Some(&message), None);
-                    self.ack_message(&message);
+                    let _guard = self.in_flight.guard();
+                    match handler(Some(&message), None) {
+                        Ok(()) => self.ack_message(&message),
+                        Err(_) => self.handle_failure(&message),
+                    }
                 }
             }
             None => {}
         }
     }
 
+    /// Called when a handler returns `Err(HandlerError)` for a message. Forwards the
+    /// message to the configured dead-letter queue once `max_receive_count` has been
+    /// exceeded; otherwise leaves it on the source queue (optionally setting its
+    /// visibility timeout to `retry_visibility_timeout`) so SQS redelivers it after the
+    /// timeout elapses.
+    fn handle_failure(&self, message: &Message) {
+        if let Some(max_receive_count) = self.max_receive_count {
+            let receive_count = message
+                .attributes
+                .as_ref()
+                .and_then(|attrs| attrs.get(APPROXIMATE_RECEIVE_COUNT))
+                .and_then(|count| count.parse::<i64>().ok())
+                .unwrap_or(0);
+
+            if exceeds_max_receive_count(receive_count, max_receive_count) {
+                self.send_to_dead_letter_queue(message);
+                return;
+            }
+        }
+
+        if let Some(timeout) = self.retry_visibility_timeout {
+            self.change_message_visibility(message, timeout);
+        }
+    }
+
+    /// Forwards `message` to the configured dead-letter queue and only then deletes it from the
+    /// source queue, so a failed `send_message` (throttling, permissions, network) leaves the
+    /// message on the source queue to be redelivered instead of silently dropping it.
+    fn send_to_dead_letter_queue(&self, message: &Message) {
+        let dead_letter_queue_url = match &self.dead_letter_queue_url {
+            Some(dead_letter_queue_url) => dead_letter_queue_url,
+            None => return,
+        };
+
+        let sent = self
+            .sqs_client
+            .send_message(SendMessageRequest {
+                queue_url: dead_letter_queue_url.clone(),
+                message_body: message.body.clone().unwrap_or_default(),
+                ..SendMessageRequest::default()
+            })
+            .sync();
+
+        if sent.is_ok() {
+            self.ack_message(message);
+        }
+    }
+
+    fn change_message_visibility(&self, message: &Message, visibility_timeout: i64) {
+        if let Some(receipt_handle) = &message.receipt_handle {
+            let _ignored = self
+                .sqs_client
+                .change_message_visibility(ChangeMessageVisibilityRequest {
+                    queue_url: self.queue_url.clone(),
+                    receipt_handle: receipt_handle.clone(),
+                    visibility_timeout,
+                })
+                .sync();
+        }
+    }
+
     fn ack_message(&self, message: &Message) {
         if message.receipt_handle.is_none() {
             return;
@@ -156,3 +297,43 @@ impl SQSListen {
             .sync();
     }
 }
+
+/// Returned by [`SQSListen::listen`]. Wraps the underlying [`ScheduleHandle`] with tracking of
+/// in-flight handler invocations, so callers can either tear the poller down immediately with
+/// [`Self::stop`] or drain it cleanly with [`Self::shutdown`].
+pub struct SQSListenHandle {
+    schedule_handle: ScheduleHandle,
+    in_flight: InFlight,
+}
+
+impl SQSListenHandle {
+    /// Stops the poller immediately, without waiting for in-flight handlers to finish. Messages
+    /// whose handler hasn't returned yet are left un-acknowledged at whatever point they were
+    /// interrupted. Kept for backward compatibility; prefer [`Self::shutdown`] for a clean drain.
+    pub fn stop(self) {
+        self.schedule_handle.stop();
+    }
+
+    /// Stops issuing new `receive_message` polls, then waits up to `grace_period` for every
+    /// in-flight handler invocation (and its pending acknowledgment) to finish before tearing
+    /// down the poller thread. Returns [`ShutdownOutcome::TimedOut`] if `grace_period` elapses
+    /// first, so callers can decide whether to force-stop or wait longer.
+    pub fn shutdown(self, grace_period: Duration) -> ShutdownOutcome {
+        let outcome = self.in_flight.drain_blocking(grace_period);
+        self.schedule_handle.stop();
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards_to_dead_letter_queue_only_once_max_receive_count_is_exceeded() {
+        assert!(!exceeds_max_receive_count(1, 3));
+        assert!(!exceeds_max_receive_count(2, 3));
+        assert!(!exceeds_max_receive_count(3, 3));
+        assert!(exceeds_max_receive_count(4, 3));
+    }
+}
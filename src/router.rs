@@ -0,0 +1,244 @@
+//! Attribute-based message routing to multiple typed handlers.
+//!
+//! A single catch-all handler is awkward for pipelines where messages carry a routing key (e.g.
+//! a `topic` message attribute). [`SQSRouter`] dispatches each received message to the first
+//! registered route whose predicate matches its `message_attributes`, falling back to a default
+//! handler, and only acknowledges the message once the selected handler succeeds.
+//!
+//! ```rust,no_run
+//! use sqslisten::{ReceiveMessageRequest, Region, SQSRouterBuilder};
+//!
+//! # async fn run() {
+//! let router = SQSRouterBuilder::new(Region::UsEast1)
+//!     .queue_url("<queue_url>")
+//!     .route(
+//!         |message| {
+//!             message
+//!                 .message_attributes
+//!                 .as_ref()
+//!                 .and_then(|attrs| attrs.get("topic"))
+//!                 .and_then(|attr| attr.string_value.as_deref())
+//!                 == Some("temperature")
+//!         },
+//!         |message| {
+//!             println!("Temperature reading: {:?}", message);
+//!             async { Ok(()) }
+//!         },
+//!     )
+//!     .default_handler(|message| {
+//!         println!("Unrouted message: {:?}", message);
+//!         async { Ok(()) }
+//!     })
+//!     .build();
+//!
+//! router.listen(ReceiveMessageRequest::default()).await;
+//! # }
+//! ```
+
+use crate::shutdown::InFlight;
+use crate::{AsyncShutdownHandle, HandlerError};
+use rusoto_core::Region;
+use rusoto_sqs::{DeleteMessageRequest, Message, ReceiveMessageRequest, Sqs, SqsClient};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// SQS's maximum long-poll wait, used as the default `wait_time_seconds` so a caller who leaves
+/// it unset gets long-polling (and therefore natural pacing) instead of a busy loop.
+const DEFAULT_WAIT_TIME_SECONDS: i64 = 20;
+
+/// How long to back off after a `receive_message` error before polling again, so a persistent
+/// failure doesn't spin against the API with zero delay.
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+type BoxFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+type RouteHandler = Box<dyn Fn(&Message) -> BoxFuture + Send + Sync>;
+type RoutePredicate = Box<dyn Fn(&Message) -> bool + Send + Sync>;
+type UnmatchedHandler = Box<dyn Fn(&Message) + Send + Sync>;
+
+/// Outcome of routing a single message, returned by [`SQSRouter::dispatch`] so callers polling
+/// programmatically (or tests) can observe routing misses instead of them silently redelivering
+/// forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteOutcome {
+    /// A route (or the default handler) matched and succeeded; the message was acknowledged.
+    Handled,
+    /// A route (or the default handler) matched but returned `Err`; the message was left for
+    /// redelivery.
+    HandlerFailed,
+    /// No route matched and no default handler was registered; the message was left for
+    /// redelivery and `on_unmatched`, if configured, was invoked.
+    Unmatched,
+}
+
+struct Route {
+    matches: RoutePredicate,
+    handler: RouteHandler,
+}
+
+/// Builds an [`SQSRouter`].
+pub struct SQSRouterBuilder {
+    region: Region,
+    queue_url: Option<String>,
+    routes: Vec<Route>,
+    default: Option<RouteHandler>,
+    on_unmatched: Option<UnmatchedHandler>,
+}
+
+impl SQSRouterBuilder {
+    pub fn new(region: Region) -> Self {
+        SQSRouterBuilder {
+            region,
+            queue_url: None,
+            routes: Vec::new(),
+            default: None,
+            on_unmatched: None,
+        }
+    }
+
+    pub fn queue_url(mut self, queue_url: impl Into<String>) -> Self {
+        self.queue_url = Some(queue_url.into());
+        self
+    }
+
+    /// Registers a handler invoked for each received message whose `matches` predicate returns
+    /// `true`. Routes are tried in registration order and only the first matching one runs.
+    pub fn route<P, F, Fut>(mut self, matches: P, handler: F) -> Self
+    where
+        P: Fn(&Message) -> bool + Send + Sync + 'static,
+        F: Fn(&Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        self.routes.push(Route {
+            matches: Box::new(matches),
+            handler: Box::new(move |message| Box::pin(handler(message))),
+        });
+        self
+    }
+
+    /// Registers a fallback handler invoked when no route matches a received message.
+    pub fn default_handler<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(&Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        self.default = Some(Box::new(move |message| Box::pin(handler(message))));
+        self
+    }
+
+    /// Registers a callback invoked whenever a received message matches no route and no
+    /// `default_handler` is registered, so callers can observe (e.g. log or alert on) routing
+    /// misses instead of the message silently redelivering forever.
+    pub fn on_unmatched<C>(mut self, callback: C) -> Self
+    where
+        C: Fn(&Message) + Send + Sync + 'static,
+    {
+        self.on_unmatched = Some(Box::new(callback));
+        self
+    }
+
+    pub fn build(self) -> SQSRouter {
+        SQSRouter {
+            sqs_client: SqsClient::new(self.region),
+            queue_url: self.queue_url.expect("queue_url is required"),
+            routes: self.routes,
+            default: self.default,
+            on_unmatched: self.on_unmatched,
+            in_flight: InFlight::new(),
+        }
+    }
+}
+
+/// Polls a queue and dispatches each message to the first matching route. See the
+/// [module docs](self) for an overview.
+pub struct SQSRouter {
+    sqs_client: SqsClient,
+    queue_url: String,
+    routes: Vec<Route>,
+    default: Option<RouteHandler>,
+    on_unmatched: Option<UnmatchedHandler>,
+    in_flight: InFlight,
+}
+
+impl SQSRouter {
+    /// Returns a handle that can be used to gracefully stop [`Self::listen`] from another task:
+    /// new polls stop first, then in-flight handler invocations get a grace period to finish.
+    pub fn shutdown_handle(&self) -> AsyncShutdownHandle {
+        AsyncShutdownHandle::new(self.in_flight.clone())
+    }
+
+    /// Polls `input.queue_url` in a loop, routing each received message to its matching handler
+    /// and acknowledging it only once that handler resolves to `Ok(())`. Returns once a
+    /// [`Self::shutdown_handle`] requests a stop.
+    pub async fn listen(&self, input: ReceiveMessageRequest) {
+        let mut input = input;
+        input.queue_url = self.queue_url.clone();
+        input.wait_time_seconds = Some(input.wait_time_seconds.unwrap_or(DEFAULT_WAIT_TIME_SECONDS));
+
+        let mut message_attribute_names = input.message_attribute_names.unwrap_or_default();
+        if !message_attribute_names.iter().any(|a| a == "All") {
+            message_attribute_names.push("All".to_string());
+        }
+        input.message_attribute_names = Some(message_attribute_names);
+
+        while !self.in_flight.is_stopping() {
+            match self.sqs_client.receive_message(input.clone()).await {
+                Ok(response) => {
+                    if let Some(messages) = response.messages {
+                        for message in messages {
+                            self.dispatch(message).await;
+                        }
+                    }
+                }
+                Err(_ignored) => {
+                    tokio::time::sleep(ERROR_BACKOFF).await;
+                }
+            }
+        }
+    }
+
+    fn route_for(&self, message: &Message) -> Option<&RouteHandler> {
+        self.routes
+            .iter()
+            .find(|route| (route.matches)(message))
+            .map(|route| &route.handler)
+            .or(self.default.as_ref())
+    }
+
+    /// Routes `message` to its matching handler (or the default), acknowledging it only once
+    /// that handler succeeds, and reports what happened via the returned [`RouteOutcome`]. Public
+    /// so a caller driving its own poll loop (or a test) can observe `Handled`/`HandlerFailed`/
+    /// `Unmatched` directly; [`Self::listen`] calls this for every received message but discards
+    /// the outcome, relying on `on_unmatched` for the one case it's worth reacting to inline.
+    pub async fn dispatch(&self, message: Message) -> RouteOutcome {
+        let _guard = self.in_flight.guard();
+        let handler = match self.route_for(&message) {
+            Some(handler) => handler,
+            None => {
+                if let Some(on_unmatched) = &self.on_unmatched {
+                    on_unmatched(&message);
+                }
+                return RouteOutcome::Unmatched;
+            }
+        };
+
+        if handler(&message).await.is_ok() {
+            self.ack_message(&message).await;
+            RouteOutcome::Handled
+        } else {
+            RouteOutcome::HandlerFailed
+        }
+    }
+
+    async fn ack_message(&self, message: &Message) {
+        if let Some(receipt_handle) = &message.receipt_handle {
+            let _ignored = self
+                .sqs_client
+                .delete_message(DeleteMessageRequest {
+                    queue_url: self.queue_url.clone(),
+                    receipt_handle: receipt_handle.clone(),
+                })
+                .await;
+        }
+    }
+}
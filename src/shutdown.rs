@@ -0,0 +1,164 @@
+//! Shared in-flight tracking and graceful drain, reused by every listener flavor so they all stop
+//! the same way: new polls stop first, then in-flight handlers get a grace period to finish.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Outcome of a graceful shutdown/drain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// Every handler that was in flight when shutdown began completed within the grace period.
+    Drained,
+    /// The grace period elapsed before every in-flight handler finished.
+    TimedOut,
+}
+
+/// Tracks how many handler invocations are currently in flight and whether new polls should
+/// stop being issued. Cheap to clone; every clone shares the same counters.
+#[derive(Clone, Default)]
+pub(crate) struct InFlight {
+    count: Arc<AtomicUsize>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl InFlight {
+    pub(crate) fn new() -> Self {
+        InFlight::default()
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Marks one handler invocation as in flight. The returned guard decrements the count again
+    /// when dropped, so it should be held for exactly the duration of that invocation.
+    pub(crate) fn guard(&self) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard {
+            count: self.count.clone(),
+        }
+    }
+
+    fn request_stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    /// Stops new polls and blocks the current thread until every in-flight handler finishes or
+    /// `grace_period` elapses, whichever comes first.
+    pub(crate) fn drain_blocking(&self, grace_period: Duration) -> ShutdownOutcome {
+        self.request_stop();
+
+        let deadline = Instant::now() + grace_period;
+        loop {
+            if self.count.load(Ordering::SeqCst) == 0 {
+                return ShutdownOutcome::Drained;
+            }
+            if Instant::now() >= deadline {
+                return ShutdownOutcome::TimedOut;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Async counterpart of [`Self::drain_blocking`] for listeners driven by a tokio runtime.
+    pub(crate) async fn drain(&self, grace_period: Duration) -> ShutdownOutcome {
+        self.request_stop();
+
+        let deadline = Instant::now() + grace_period;
+        loop {
+            if self.count.load(Ordering::SeqCst) == 0 {
+                return ShutdownOutcome::Drained;
+            }
+            if Instant::now() >= deadline {
+                return ShutdownOutcome::TimedOut;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+pub(crate) struct InFlightGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drain_blocking_returns_drained_once_every_guard_is_dropped() {
+        let in_flight = InFlight::new();
+        let guard = in_flight.guard();
+        drop(guard);
+
+        assert_eq!(
+            in_flight.drain_blocking(Duration::from_millis(200)),
+            ShutdownOutcome::Drained
+        );
+        assert!(in_flight.is_stopping());
+    }
+
+    #[test]
+    fn drain_blocking_times_out_while_a_guard_is_still_held() {
+        let in_flight = InFlight::new();
+        let _guard = in_flight.guard();
+
+        assert_eq!(
+            in_flight.drain_blocking(Duration::from_millis(100)),
+            ShutdownOutcome::TimedOut
+        );
+    }
+
+    #[tokio::test]
+    async fn drain_returns_drained_once_every_guard_is_dropped() {
+        let in_flight = InFlight::new();
+        let guard = in_flight.guard();
+        drop(guard);
+
+        assert_eq!(
+            in_flight.drain(Duration::from_millis(200)).await,
+            ShutdownOutcome::Drained
+        );
+        assert!(in_flight.is_stopping());
+    }
+
+    #[tokio::test]
+    async fn drain_times_out_while_a_guard_is_still_held() {
+        let in_flight = InFlight::new();
+        let _guard = in_flight.guard();
+
+        assert_eq!(
+            in_flight.drain(Duration::from_millis(100)).await,
+            ShutdownOutcome::TimedOut
+        );
+    }
+}
+
+/// A handle for gracefully stopping [`crate::SQSListener`], [`crate::SQSBroadcastListener`], or
+/// [`crate::SQSRouter`] from another task, mirroring [`crate::SQSListenHandle::shutdown`] for the
+/// legacy sync listener. Obtained from the listener's `shutdown_handle` method and cheap to
+/// clone.
+#[derive(Clone)]
+pub struct AsyncShutdownHandle {
+    in_flight: InFlight,
+}
+
+impl AsyncShutdownHandle {
+    pub(crate) fn new(in_flight: InFlight) -> Self {
+        AsyncShutdownHandle { in_flight }
+    }
+
+    /// Stops the listener's loop from issuing new polls, then waits up to `grace_period` for
+    /// every in-flight handler invocation to finish before returning.
+    pub async fn shutdown(&self, grace_period: Duration) -> ShutdownOutcome {
+        self.in_flight.drain(grace_period).await
+    }
+}